@@ -16,10 +16,54 @@
 //!
 //! ### Grammar:
 //! - comp: mapping for_if_clause+
-//! >>> exmple 1: ... for_if_clause 
+//! >>> exmple 1: ... for_if_clause
 //! >>> exmple 2: ... for_if_clause for_if_clause ...
 //!
-//! - mapping: expression
+//! Nested `for_if_clause`s are folded from innermost to outermost: the
+//! innermost clause wraps the *mapping* in a `.then(...)` guard, and each
+//! enclosing clause wraps the already-built *inner iterator* in its own
+//! `flat_map`, e.g. `comp![x*y for x in xs for y in ys if x != y]` lowers,
+//! in effect (the exact expansion adds the guard described below), to
+//!
+//! ```rust
+//! IntoIterator::into_iter(xs)
+//!   .flat_map(move |x| {
+//!     IntoIterator::into_iter(ys)
+//!       .flat_map(move |y| {
+//!         (true && x != y).then(|| x * y)
+//!       })
+//!   })
+//! ```
+//!
+//! An enclosing clause's own conditions can't be expressed with `.then`
+//! the same way, since that would make its `flat_map` yield whole
+//! sub-iterators instead of flattening their elements. Instead they guard
+//! the already-built inner iterator and fall back to an empty one, e.g.
+//! `comp![x*y for x in xs if x > 0 for y in ys if x != y]` lowers to
+//!
+//! ```rust
+//! IntoIterator::into_iter(xs)
+//!   .flat_map(move |x| {
+//!     if true && x > 0 {
+//!       Some(
+//!         IntoIterator::into_iter(ys)
+//!           .flat_map(move |y| {
+//!             (true && x != y).then(|| x * y)
+//!           })
+//!       )
+//!     } else {
+//!       None
+//!     }.into_iter().flatten()
+//!   })
+//! ```
+//!
+//! Every enclosing clause gets this guard, including one with no `if`
+//! (its guard is then just `if true`), so the first example above is
+//! emitted in this guarded form as well.
+//!
+//! - mapping: expression | expression '=>' expression
+//! >>> example 1 (comp!/comp_set!): mapping
+//! >>> example 2 (comp_map!): key => value
 //!
 //! - for_if_clause:
 //!  | 'for' pattern 'in' expression ('if' expression)*
@@ -27,9 +71,11 @@
 //! >>> example 2: for ... in ... if ...
 //! >>> example 3: for ... in ... if ... if ...
 //!
-//! - pattern: name (,name)*
+//! - pattern: any irrefutable pattern accepted by `syn::Pat::parse_single`
 //! >>> example 1: a
-//! >>> example 2: a, b
+//! >>> example 2: (a, b)
+//! >>> example 3: Point { x, y }
+//! >>> example 4: (a, (b, _))
 //!
 //! ### Rust syntax
 //! In the case of 
@@ -54,14 +100,22 @@
 //!     (true (&& <expression>)*).then(|| <mapping>)
 //!   })
 //! ```
+//!
+//! ### comp_map! / comp_set!
+//! `comp_map!` and `comp_set!` share the grammar and expansion above, but
+//! the `key => value` form of `mapping` is only meaningful for `comp_map!`
+//! (for `comp_set!` the single-expression form is used, same as `comp!`).
+//! Unlike `comp!`, which yields a lazy iterator, `comp_map!`/`comp_set!`
+//! bake a trailing `.collect()` into the expansion, so they evaluate to a
+//! finished `HashMap`/`HashSet` rather than something you still need to
+//! collect yourself.
 
 use syn::parse::{Parse, ParseStream};
 use quote::{quote, ToTokens};
 use proc_macro::TokenStream;
 use proc_macro2::TokenStream as TokenStream2;
 
-// Implement the simpler comprehension only
-// comp: mapping for_if_clause
+// comp: mapping for_if_clause+
 // for_if_clause:
 //  | 'for' pattern 'in' expression ('if' expression)*
 // pattern: name (,name)*
@@ -72,55 +126,120 @@ pub fn comp(input: TokenStream) -> TokenStream {
     quote! { #c }.into()
 }
 
+#[proc_macro]
+pub fn comp_map(input: TokenStream) -> TokenStream {
+    let c: Comp = syn::parse_macro_input!(input as Comp);
+    let iter = c.build();
+    quote! { #iter.collect::<std::collections::HashMap<_, _>>() }.into()
+}
+
+#[proc_macro]
+pub fn comp_set(input: TokenStream) -> TokenStream {
+    let c: Comp = syn::parse_macro_input!(input as Comp);
+    let iter = c.build();
+    quote! { #iter.collect::<std::collections::HashSet<_>>() }.into()
+}
+
 
-/// comp: mapping for_if_clause
+/// comp: mapping for_if_clause+
 struct Comp {
     mapping: Mapping,
-    for_if_clause: ForIfClause,
+    for_if_clauses: Vec<ForIfClause>,
 }
 // frontend
 impl Parse for Comp {
     fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mapping: Mapping = input.parse()?;
+        // the grammar requires at least one for_if_clause, so parse it
+        // eagerly instead of letting parse_zero_or_more accept zero
+        let first: ForIfClause = input.parse()?;
+        let mut for_if_clauses = vec![first];
+        for_if_clauses.extend(parse_zero_or_more::<ForIfClause>(input)?);
         Ok(Self {
-            mapping: input.parse::<Mapping>()?,
-            for_if_clause: input.parse::<ForIfClause>()?,
+            mapping,
+            for_if_clauses,
         })
     }
 }
-// backend
-impl ToTokens for Comp {
-    fn to_tokens(&self, tokens: &mut TokenStream2) {
-        // core::iter::IntoIterator::into_iter(<sequence>)
-        //   .flat_map(|<pattern>| {
-        //     (true (&& <expression>)*).then(|| <mapping>)
-        //   })
-        let Mapping(mapping) = &self.mapping;
-        let ForIfClause {
-            pattern, sequence, conditions,
-        } = &self.for_if_clause;
-
-        tokens.extend(quote! {
+impl Comp {
+    // core::iter::IntoIterator::into_iter(<sequence>)
+    //   .flat_map(|<pattern>| {
+    //     (true (&& <expression>)*).then(|| <mapping>)
+    //   })
+    // folded from the innermost for_if_clause (closest to the mapping)
+    // out to the outermost one, so each clause's conditions are
+    // evaluated in that clause's scope and outer closures capture the
+    // pattern bindings of the clauses they enclose.
+    //
+    // Only the innermost clause's flat_map may return `.then(...)`
+    // directly: its Option<mapping> is what flat_map flattens into
+    // zero-or-one elements. An enclosing clause's flat_map must instead
+    // return the already-built inner iterator as-is, so that flat_map
+    // flattens *its* elements rather than treating the whole iterator as
+    // a single item. An enclosing clause's own conditions therefore can't
+    // live in a `.then` there either; they're applied as a guard that
+    // chooses between the inner iterator and an empty one.
+    fn build(&self) -> TokenStream2 {
+        let mapping = &self.mapping;
+        let mut clauses = self.for_if_clauses.iter().rev();
+
+        let innermost = clauses.next().expect("comp requires at least one for_if_clause");
+        let ForIfClause { pattern, sequence, conditions } = innermost;
+        let mut inner = quote! {
             core::iter::IntoIterator::into_iter(#sequence)
                 .flat_map(move |#pattern| {
                     (true #(&& #conditions)* ).then(|| { #mapping })
                 })
-        });
+        };
+
+        for ForIfClause { pattern, sequence, conditions } in clauses {
+            inner = quote! {
+                core::iter::IntoIterator::into_iter(#sequence)
+                    .flat_map(move |#pattern| {
+                        if true #(&& #conditions)* {
+                            Some(#inner)
+                        } else {
+                            None
+                        }.into_iter().flatten()
+                    })
+            };
+        }
+
+        inner
+    }
+}
+// backend
+impl ToTokens for Comp {
+    fn to_tokens(&self, tokens: &mut TokenStream2) {
+        tokens.extend(self.build());
     }
 }
 
-/// mapping: expression
-struct Mapping (syn::Expr);
+/// mapping: expression | expression '=>' expression
+enum Mapping {
+    Value(syn::Expr),
+    KeyValue(syn::Expr, syn::Expr),
+}
 // frontend
 impl Parse for Mapping {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        // store expression into this object and return
-        input.parse().map(Self)
+        let first: syn::Expr = input.parse()?;
+        if input.peek(syn::Token![=>]) {
+            let _ = input.parse::<syn::Token![=>]>()?;
+            let value: syn::Expr = input.parse()?;
+            Ok(Self::KeyValue(first, value))
+        } else {
+            Ok(Self::Value(first))
+        }
     }
 }
 // backend
 impl ToTokens for Mapping {
     fn to_tokens(&self, tokens: &mut TokenStream2) {
-        self.0.to_tokens(tokens);
+        match self {
+            Self::Value(expr) => expr.to_tokens(tokens),
+            Self::KeyValue(key, value) => quote! { (#key, #value) }.to_tokens(tokens),
+        }
     }
 }
 
@@ -144,7 +263,7 @@ impl Parse for ForIfClause {
         // parse sequence following by the `in` token
         let sequence: syn::Expr = input.parse()?;
         // parse conditions
-        let conditions: Vec<Condition> = parse_zero_or_more(input);
+        let conditions: Vec<Condition> = parse_zero_or_more(input)?;
         Ok(Self {
             pattern,
             sequence,
@@ -153,22 +272,39 @@ impl Parse for ForIfClause {
 
     }
 }
+// a further `for_if_clause` only starts if the next token is `for`, so
+// `parse_zero_or_more` can stop without ever attempting (and discarding) a
+// failing parse
+impl Peek for ForIfClause {
+    fn peek(input: ParseStream) -> bool {
+        input.peek(syn::Token![for])
+    }
+}
 
-fn parse_zero_or_more<T: Parse>(input: ParseStream) -> Vec<T> {
+/// Lets `parse_zero_or_more` decide whether another `T` is coming up
+/// without consuming input, so a clause that *does* start (e.g. `if` is
+/// present) but is otherwise malformed reports a real `syn::Error` instead
+/// of being silently treated as "no more items".
+trait Peek {
+    fn peek(input: ParseStream) -> bool;
+}
+
+fn parse_zero_or_more<T: Parse + Peek>(input: ParseStream) -> syn::Result<Vec<T>> {
     let mut result: Vec<T> = Vec::new();
-    while let Ok(item) = input.parse::<T>() {
-        result.push(item);
+    while T::peek(input) {
+        result.push(input.parse()?);
     }
-    result
+    Ok(result)
 }
 
-/// pattern: name (, name)*
+/// pattern: any irrefutable pattern, e.g. `a`, `(a, b)`, `Point { x, y }`,
+/// `_`, `ref mut a`, or `a @ 1..=5`, as accepted by `syn::Pat::parse_single`
 struct Pattern (syn::Pat);
 // frontend
 impl Parse for Pattern {
     fn parse(input: ParseStream) -> syn::Result<Self> {
         // store expression into this object and return
-        syn::Pat::parse_single(input).map(Self) 
+        syn::Pat::parse_single(input).map(Self)
         // identical to Ok(Self(syn::Pat::parse_single(input)?))
     }
 }
@@ -185,12 +321,28 @@ struct Condition (syn::Expr);
 // frontend
 impl Parse for Condition {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        // check if the input stream starts with the `if` token
+        // only ever called once the caller has peeked an `if`, so
+        // lookahead1 reports "expected `if`" with the right span if that
+        // assumption is ever violated, instead of a generic parse failure
+        let lookahead = input.lookahead1();
+        if !lookahead.peek(syn::Token![if]) {
+            return Err(lookahead.error());
+        }
         let _ = input.parse::<syn::Token![if]>()?;
-        // store expression into this object and return
+        // store expression into this object and return; a missing or
+        // malformed expression surfaces as a real syn::Error pointing at
+        // the offending span instead of being swallowed
         input.parse().map(Self)
     }
 }
+// a further condition only starts if the next token is `if`, so
+// `parse_zero_or_more` can stop without ever attempting (and discarding) a
+// failing parse
+impl Peek for Condition {
+    fn peek(input: ParseStream) -> bool {
+        input.peek(syn::Token![if])
+    }
+}
 // backend
 impl ToTokens for Condition {
     fn to_tokens(&self, tokens: &mut TokenStream2) {
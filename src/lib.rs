@@ -1,12 +1,85 @@
-use comp_macro::comp;
+use comp_macro::{comp, comp_map, comp_set};
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::{HashMap, HashSet};
 
     #[test]
     fn it_works() {
         let res: Vec<_> = comp![x*2 for x in [1,2,3]].collect();
         assert_eq!(res, [2,4,6])
     }
+
+    #[test]
+    fn comp_map_works() {
+        let pairs = [(1, 10), (2, -20), (3, 30)];
+        let res: HashMap<_, _> = comp_map![k => v for (k,v) in pairs if v > 0];
+        assert_eq!(res, HashMap::from([(1, 10), (3, 30)]))
+    }
+
+    #[test]
+    fn comp_set_works() {
+        let xs = [1, 2, 3];
+        let res: HashSet<_> = comp_set![x*2 for x in xs];
+        assert_eq!(res, HashSet::from([2, 4, 6]))
+    }
+
+    #[test]
+    fn multiple_for_clauses_flatten_the_product() {
+        let xs = [1, 2];
+        let ys = [10, 20];
+        let res: Vec<_> = comp![x*y for x in xs for y in ys].collect();
+        assert_eq!(res, [10, 20, 20, 40])
+    }
+
+    #[test]
+    fn multiple_for_clauses_each_with_its_own_if() {
+        let xs = [1, -2, 3];
+        let ys = [10, 20];
+        let res: Vec<_> = comp![x*y for x in xs if x > 0 for y in ys if y != 20].collect();
+        assert_eq!(res, [10, 30])
+    }
+
+    #[test]
+    fn comp_map_with_multiple_for_clauses() {
+        let xs = [1, 2];
+        let ys = [10, 20];
+        let res: HashMap<_, _> = comp_map![(x,y) => x*y for x in xs for y in ys];
+        assert_eq!(res, HashMap::from([((1,10), 10), ((1,20), 20), ((2,10), 20), ((2,20), 40)]))
+    }
+
+    #[test]
+    fn comp_set_with_multiple_for_clauses() {
+        let xs = [1, 2];
+        let ys = [10, 20];
+        let res: HashSet<_> = comp_set![x*y for x in xs for y in ys];
+        assert_eq!(res, HashSet::from([10, 20, 40]))
+    }
+
+    #[test]
+    fn tuple_pattern() {
+        let pairs = [(1, 10), (2, 20), (3, 30)];
+        let res: Vec<_> = comp![x + y for (x, y) in pairs].collect();
+        assert_eq!(res, [11, 22, 33])
+    }
+
+    #[test]
+    fn nested_tuple_pattern() {
+        let nested = [(1, (2, 100)), (3, (4, 100))];
+        let res: Vec<_> = comp![a + b for (a, (b, _)) in nested].collect();
+        assert_eq!(res, [3, 7])
+    }
+
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    #[test]
+    fn struct_destructuring_pattern() {
+        let points = [Point { x: 1, y: 2 }, Point { x: 3, y: 4 }];
+        let res: Vec<_> = comp![x + y for Point { x, y } in points].collect();
+        assert_eq!(res, [3, 7])
+    }
 }